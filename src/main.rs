@@ -1,19 +1,53 @@
 use anyhow::Result as AnyResult;
 use std::{
-    env::args, fs::File, io::Write, os::unix::fs::FileExt, sync::mpsc, thread, time::Instant,
+    collections::HashSet,
+    env::args,
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::unix::fs::FileExt,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Instant,
 };
 
 use ahash::AHashMap;
+use crossbeam_queue::ArrayQueue;
 
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-const DISPATCH_LOOPS: usize = 128;
-
 const CHUNK_SIZE: u64 = 1 * 1024 * 1024;
 
 const CHUNK_EXCESS: u64 = 64;
 
+/// 8-byte signature for the pre-tokenized binary format, modeled on PNG's:
+/// a non-ASCII first byte (catches 7-bit transfers), the format name, and a
+/// CR-LF-`\x1a`-LF tail that catches CRLF/CR mangling and EOF truncation.
+const BIN_MAGIC: [u8; 8] = [0x8B, b'B', b'R', b'C', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const BIN_VERSION: u8 = 1;
+
+const BIN_HEADER_LEN: u64 = BIN_MAGIC.len() as u64 + 1;
+
+/// Fixed 32-byte key + little-endian `i32` value.
+const BIN_RECORD_LEN: u64 = 32 + 4;
+
+/// Binary-format chunk stride: a whole number of records closest to
+/// `CHUNK_SIZE`, so every worker read is already record-aligned and
+/// `read_bin_chunk` only ever has to trim the true end-of-file tail.
+const BIN_CHUNK_BYTES: u64 = (CHUNK_SIZE / BIN_RECORD_LEN) * BIN_RECORD_LEN;
+
+/// How many chunks a worker processes between checkpoint flushes.
+const CHECKPOINT_FLUSH_INTERVAL: usize = 16;
+
+/// Checkpoint blobs larger than this are split across First/Middle/Last
+/// blobs instead of one Full blob.
+const CHECKPOINT_BLOCK: usize = 64 * 1024;
+
+/// `{crc32: u32, rsize: u32, rtype: u8}`.
+const CHECKPOINT_HEADER_LEN: usize = 4 + 4 + 1;
+
+#[derive(Clone, Copy)]
 struct Record {
     max: i32,
     min: i32,
@@ -117,82 +151,802 @@ fn process_chunk_v2(buffer: &[u8]) -> AHashMap<Key, Record> {
     bmap
 }
 
-fn dispatch(file: &File, offset: u64, file_len: u64) -> AHashMap<Key, Record> {
+const MAX_NAME_LEN: usize = 32;
+
+/// The ways a `name;value` window can fail `--check` validation.
+#[derive(Clone, Copy, Debug)]
+enum FailureKind {
+    /// The row has zero or more than one `;` before its newline.
+    Delimiter,
+    /// The name was empty or longer than `MAX_NAME_LEN` bytes.
+    Name,
+    /// The value didn't match `-?[0-9]+(\.[0-9])?`.
+    Value,
+}
+
+/// Per-worker tally of validation failures, merged the same way `Record`s are.
+#[derive(Default, Clone, Copy)]
+struct ErrorCounts {
+    delimiter: u64,
+    name: u64,
+    value: u64,
+}
+
+impl ErrorCounts {
+    fn record(&mut self, kind: FailureKind) {
+        match kind {
+            FailureKind::Delimiter => self.delimiter += 1,
+            FailureKind::Name => self.name += 1,
+            FailureKind::Value => self.value += 1,
+        }
+    }
+
+    fn merge(&mut self, other: ErrorCounts) {
+        self.delimiter += other.delimiter;
+        self.name += other.name;
+        self.value += other.value;
+    }
+
+    fn total(&self) -> u64 {
+        self.delimiter + self.name + self.value
+    }
+}
+
+fn validate_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN
+}
+
+/// Matches `-?[0-9]+(\.[0-9])?`.
+fn validate_value(value: &[u8]) -> bool {
+    let value = value.strip_prefix(b"-").unwrap_or(value);
+    let (int_part, frac_part) = match memchr::memchr(b'.', value) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+
+    if int_part.is_empty() || !int_part.iter().all(u8::is_ascii_digit) {
+        return false;
+    }
+
+    match frac_part {
+        Some(f) => f.len() == 1 && f[0].is_ascii_digit(),
+        None => true,
+    }
+}
+
+/// Appends quarantined raw byte ranges to disk, guarded by a mutex since every
+/// worker shares the same quarantine file.
+struct Quarantine {
+    file: Mutex<File>,
+}
+
+impl Quarantine {
+    fn open(path: &str) -> AnyResult<Self> {
+        Ok(Quarantine {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+
+    fn append(&self, kind: FailureKind, file_offset: u64, raw: &[u8]) {
+        let line = format!(
+            "{:?}\toffset={}\t{:?}\n",
+            kind,
+            file_offset,
+            String::from_utf8_lossy(raw)
+        );
+        let mut file = self.file.lock().unwrap();
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+/// Validating counterpart to `process_chunk_v2`: every row is checked before
+/// it is aggregated. Unlike `process_chunk_v2`'s `;`/`\n` alternation, rows
+/// are split strictly by `\n` first, so a malformed row (missing or doubled
+/// `;`) only ever discards itself — it can never desync the scan and eat the
+/// row that follows. Failures are tallied in `counts` and, if `quarantine` is
+/// set, their raw bytes plus absolute file offset are appended to the
+/// quarantine file instead of poisoning the aggregates.
+fn process_chunk_checked(
+    buffer: &[u8],
+    base_offset: u64,
+    counts: &mut ErrorCounts,
+    quarantine: Option<&Quarantine>,
+) -> AHashMap<Key, Record> {
+    let mut bmap = AHashMap::<Key, Record>::with_capacity(512);
+    let mut prev_end = 0;
+
+    for line_end in memchr::memchr_iter(b'\n', buffer) {
+        let line_start = prev_end;
+        let line = &buffer[line_start..line_end];
+        prev_end = line_end + 1;
+
+        // Exactly one `;` in the row: reject both the missing and the
+        // doubled case instead of inferring the split from iterator
+        // alternation.
+        let semi = match memchr::memchr(b';', line) {
+            Some(semi) if memchr::memchr(b';', &line[semi + 1..]).is_none() => semi,
+            _ => {
+                counts.record(FailureKind::Delimiter);
+                if let Some(quarantine) = quarantine {
+                    quarantine.append(FailureKind::Delimiter, base_offset + line_start as u64, line);
+                }
+                continue;
+            }
+        };
+
+        let name = &line[..semi];
+        let value_bytes = &line[semi + 1..];
+
+        let failure = if !validate_name(name) {
+            Some(FailureKind::Name)
+        } else if !validate_value(value_bytes) {
+            Some(FailureKind::Value)
+        } else {
+            None
+        };
+
+        if let Some(kind) = failure {
+            counts.record(kind);
+            if let Some(quarantine) = quarantine {
+                quarantine.append(kind, base_offset + line_start as u64, line);
+            }
+            continue;
+        }
+
+        let mut arr = Key::default();
+        arr[..name.len()].copy_from_slice(name);
+        let value = fixed_point_parse(value_bytes);
+
+        if let Some(record) = bmap.get_mut(&arr) {
+            record.count += 1;
+            record.sum += value;
+            record.max = record.max.max(value);
+            record.min = record.min.min(value);
+        } else {
+            bmap.insert(arr, Record::new(value));
+        }
+    }
+    bmap
+}
+
+/// Reads and validates the binary format's header, returning the offset its
+/// records start at. Rejects text files (or anything else) fed to the binary
+/// path by checking the signature before trusting the version byte.
+fn read_bin_header(file: &File) -> AnyResult<u64> {
+    let mut header = [0u8; BIN_HEADER_LEN as usize];
+    file.read_exact_at(&mut header, 0)
+        .map_err(|_| anyhow::anyhow!("file is too short to be a brc binary file"))?;
+
+    if header[..BIN_MAGIC.len()] != BIN_MAGIC {
+        anyhow::bail!("not a brc binary file (signature mismatch)");
+    }
+
+    let version = header[BIN_MAGIC.len()];
+    if version != BIN_VERSION {
+        anyhow::bail!("unsupported brc binary version {version}");
+    }
+
+    Ok(BIN_HEADER_LEN)
+}
+
+/// Writes one `name;value` window's record in binary form: a fixed 32-byte
+/// key followed by a little-endian `i32` value.
+fn write_bin_record(out: &mut impl Write, name: &[u8], value: i32) -> AnyResult<()> {
+    let mut key = Key::default();
+    let name_len = name.len().min(MAX_NAME_LEN);
+    key[..name_len].copy_from_slice(&name[..name_len]);
+
+    out.write_all(&key)?;
+    out.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// Converts a text measurements file into the pre-tokenized binary format so
+/// repeated runs over the same dataset can skip `memchr`/`fixed_point_parse`
+/// entirely. Streams the input through `read_chunk`'s fixed 1 MB buffer, the
+/// same way `worker` does, so converting a multi-hundred-GB file
+/// doesn't require holding it in RAM.
+fn convert_to_binary(input_path: &str, output_path: &str) -> AnyResult<()> {
+    let file = File::open(input_path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut out = BufWriter::new(File::create(output_path)?);
+    out.write_all(&BIN_MAGIC)?;
+    out.write_all(&[BIN_VERSION])?;
+
     let mut buffer = [0; (CHUNK_SIZE + CHUNK_EXCESS) as usize];
-    let mut map = AHashMap::<Key, Record>::with_capacity(1024);
+    let mut offset = 0;
+    while offset < file_len {
+        let (start, end) = read_chunk(&file, offset, &mut buffer);
+        let window = &buffer[start..end];
+
+        let mut iter = memchr::memchr2_iter(b';', b'\n', window);
+        let mut prev_end = 0;
+        while let (Some(semi), Some(end)) = (iter.next(), iter.next()) {
+            let value = fixed_point_parse(&window[semi + 1..end]);
+            write_bin_record(&mut out, &window[prev_end..semi], value)?;
+            prev_end = end + 1;
+        }
+
+        offset += CHUNK_SIZE;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Reads a range of the binary format aligned to `BIN_RECORD_LEN` so callers
+/// never see a partial record, the binary-format analogue of `read_chunk`.
+/// Like `read_chunk`, tries to fill the whole buffer with `read_exact_at`
+/// first and only falls back to a short `read_at` at the true end of file —
+/// a single `read_at` can return fewer bytes than requested even mid-file,
+/// which would otherwise silently drop the untrimmed remainder.
+fn read_bin_chunk(file: &File, offset: u64, buffer: &mut [u8]) -> usize {
+    let n = if file.read_exact_at(buffer, offset).is_ok() {
+        buffer.len()
+    } else {
+        match file.read_at(buffer, offset) {
+            Ok(n) => n,
+            Err(_) => return 0,
+        }
+    };
+    n - (n % BIN_RECORD_LEN as usize)
+}
+
+/// Binary-format analogue of `process_chunk_v2`: records are already
+/// tokenized, so this is a plain stride over `BIN_RECORD_LEN`-byte records.
+fn process_chunk_binary(buffer: &[u8]) -> AHashMap<Key, Record> {
+    let mut bmap = AHashMap::<Key, Record>::with_capacity(512);
 
-    for i in 0..DISPATCH_LOOPS {
-        if (offset + CHUNK_SIZE * (i as u64)) >= file_len {
+    for rec in buffer.chunks_exact(BIN_RECORD_LEN as usize) {
+        let mut arr = Key::default();
+        arr.copy_from_slice(&rec[..32]);
+        let value = i32::from_le_bytes(rec[32..36].try_into().unwrap());
+
+        if let Some(record) = bmap.get_mut(&arr) {
+            record.count += 1;
+            record.sum += value;
+            record.max = record.max.max(value);
+            record.min = record.min.min(value);
+        } else {
+            bmap.insert(arr, Record::new(value));
+        }
+    }
+
+    bmap
+}
+
+/// A checkpoint blob's `rtype`: a map too big for one blob spans consecutive
+/// First/Middle/Last blobs; anything that fits in one block is Full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+enum BlobType {
+    Full = 0,
+    First = 1,
+    Middle = 2,
+    Last = 3,
+}
+
+impl BlobType {
+    fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(BlobType::Full),
+            1 => Some(BlobType::First),
+            2 => Some(BlobType::Middle),
+            3 => Some(BlobType::Last),
+            _ => None,
+        }
+    }
+}
+
+/// Append-only, CRC32-protected log of checkpoint blobs shared by every
+/// worker. Each blob is a `{crc32, rsize, rtype}` header followed by `rsize`
+/// payload bytes; a worker's progress since its last flush is serialized and
+/// appended as one Full blob, or split across First..Last blobs if it's
+/// bigger than `CHECKPOINT_BLOCK`.
+struct Checkpoint {
+    file: Mutex<File>,
+}
+
+impl Checkpoint {
+    fn create(path: &str) -> AnyResult<Self> {
+        Ok(Checkpoint {
+            file: Mutex::new(File::options().create(true).append(true).open(path)?),
+        })
+    }
+
+    fn write_blob(file: &mut File, rtype: BlobType, payload: &[u8]) -> AnyResult<()> {
+        file.write_all(&crc32fast::hash(payload).to_le_bytes())?;
+        file.write_all(&(payload.len() as u32).to_le_bytes())?;
+        file.write_all(&[rtype as u8])?;
+        file.write_all(payload)?;
+        Ok(())
+    }
+
+    /// Appends one worker's finished chunk offsets plus its partial map
+    /// accumulated since the last flush.
+    fn append(&self, offsets: &[u64], map: &AHashMap<Key, Record>) -> AnyResult<()> {
+        let payload = serialize_progress(offsets, map);
+        let mut file = self.file.lock().unwrap();
+
+        if payload.len() <= CHECKPOINT_BLOCK {
+            Self::write_blob(&mut file, BlobType::Full, &payload)?;
+        } else {
+            let chunks: Vec<&[u8]> = payload.chunks(CHECKPOINT_BLOCK).collect();
+            let last = chunks.len() - 1;
+            for (i, chunk) in chunks.iter().enumerate() {
+                let rtype = if i == 0 {
+                    BlobType::First
+                } else if i == last {
+                    BlobType::Last
+                } else {
+                    BlobType::Middle
+                };
+                Self::write_blob(&mut file, rtype, chunk)?;
+            }
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+}
+
+fn serialize_progress(offsets: &[u64], map: &AHashMap<Key, Record>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8 + offsets.len() * 8 + map.len() * 48);
+
+    buf.extend_from_slice(&(offsets.len() as u32).to_le_bytes());
+    for offset in offsets {
+        buf.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    buf.extend_from_slice(&(map.len() as u32).to_le_bytes());
+    for (key, rec) in map {
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(&rec.max.to_le_bytes());
+        buf.extend_from_slice(&rec.min.to_le_bytes());
+        buf.extend_from_slice(&rec.count.to_le_bytes());
+        buf.extend_from_slice(&rec.sum.to_le_bytes());
+    }
+
+    buf
+}
+
+/// Parses a `serialize_progress` payload, folding its records into `map` and
+/// its finished offsets into `offsets`.
+fn deserialize_progress(payload: &[u8], offsets: &mut Vec<u64>, map: &mut AHashMap<Key, Record>) {
+    let mut pos = 0;
+
+    let num_offsets = u32::from_le_bytes(payload[pos..pos + 4].try_into().unwrap()) as usize;
+    pos += 4;
+    for _ in 0..num_offsets {
+        offsets.push(u64::from_le_bytes(payload[pos..pos + 8].try_into().unwrap()));
+        pos += 8;
+    }
+
+    let num_records = u32::from_le_bytes(payload[pos..pos + 4].try_into().unwrap()) as usize;
+    pos += 4;
+    for _ in 0..num_records {
+        let mut key = Key::default();
+        key.copy_from_slice(&payload[pos..pos + 32]);
+        pos += 32;
+
+        let rec = Record {
+            max: i32::from_le_bytes(payload[pos..pos + 4].try_into().unwrap()),
+            min: i32::from_le_bytes(payload[pos + 4..pos + 8].try_into().unwrap()),
+            count: i32::from_le_bytes(payload[pos + 8..pos + 12].try_into().unwrap()),
+            sum: i32::from_le_bytes(payload[pos + 12..pos + 16].try_into().unwrap()),
+        };
+        pos += 16;
+
+        if let Some(existing) = map.get_mut(&key) {
+            existing.count += rec.count;
+            existing.sum += rec.sum;
+            existing.max = existing.max.max(rec.max);
+            existing.min = existing.min.min(rec.min);
+        } else {
+            map.insert(key, rec);
+        }
+    }
+}
+
+/// Scans an existing checkpoint file sequentially, verifying each blob's
+/// CRC32 and stopping at the first blob whose CRC fails or whose payload is
+/// truncated (the interrupted tail from a crash mid-write, discarded), then
+/// replays every surviving Full blob and First..Last group to rebuild the
+/// merged map and the set of chunk offsets that don't need reprocessing.
+fn recover_checkpoint(path: &str) -> AnyResult<(AHashMap<Key, Record>, HashSet<u64>)> {
+    let mut map = AHashMap::<Key, Record>::new();
+    let mut done = HashSet::new();
+
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return Ok((map, done)),
+    };
+
+    let mut pending = Vec::new();
+    let mut pos: u64 = 0;
+
+    loop {
+        let mut header = [0u8; CHECKPOINT_HEADER_LEN];
+        if file.read_exact_at(&mut header, pos).is_err() {
             break;
         }
-        let (start, end) = read_chunk(&file, offset + (CHUNK_SIZE * i as u64), &mut buffer);
-        let l_map = process_chunk_v2(&buffer[start..end]);
 
-        for (key, other) in l_map {
-            if let Some(record) = map.get_mut(&key) {
-                record.count += other.count;
-                record.sum += other.sum;
-                record.max = record.max.max(other.max);
-                record.min = record.min.min(other.min);
-            } else {
-                map.insert(key, other);
+        let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let rsize = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let rtype = match BlobType::from_u8(header[8]) {
+            Some(t) => t,
+            None => break,
+        };
+
+        let mut payload = vec![0u8; rsize as usize];
+        if file
+            .read_exact_at(&mut payload, pos + CHECKPOINT_HEADER_LEN as u64)
+            .is_err()
+        {
+            break;
+        }
+        if crc32fast::hash(&payload) != crc {
+            break;
+        }
+
+        match rtype {
+            BlobType::Full => {
+                let mut offsets = Vec::new();
+                deserialize_progress(&payload, &mut offsets, &mut map);
+                done.extend(offsets);
+            }
+            BlobType::First => {
+                pending.clear();
+                pending.extend_from_slice(&payload);
+            }
+            BlobType::Middle => pending.extend_from_slice(&payload),
+            BlobType::Last => {
+                pending.extend_from_slice(&payload);
+                let mut offsets = Vec::new();
+                deserialize_progress(&pending, &mut offsets, &mut map);
+                done.extend(offsets);
+                pending.clear();
             }
         }
+
+        pos += CHECKPOINT_HEADER_LEN as u64 + rsize as u64;
     }
 
-    map
+    Ok((map, done))
 }
 
-fn main() -> AnyResult<()> {
+/// The result sink: either the plain output file or one wrapped in a
+/// streaming compressor, so the per-record `format!` loop in `main` never
+/// has to know which.
+enum OutputWriter {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<flate2::write::GzEncoder<File>>),
+    Zstd(BufWriter<zstd::Encoder<'static, File>>),
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Plain(w) => w.write(buf),
+            OutputWriter::Gzip(w) => w.write(buf),
+            OutputWriter::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(w) => w.flush(),
+            OutputWriter::Gzip(w) => w.flush(),
+            OutputWriter::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+impl OutputWriter {
+    /// Flushes the buffer and, for the compressed variants, writes the
+    /// trailing frame footer.
+    fn finish(self) -> AnyResult<()> {
+        match self {
+            OutputWriter::Plain(mut w) => w.flush()?,
+            OutputWriter::Gzip(w) => {
+                w.into_inner().map_err(|e| e.into_error())?.finish()?;
+            }
+            OutputWriter::Zstd(w) => {
+                w.into_inner().map_err(|e| e.into_error())?.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Opens the result sink for `path`, wrapping it in a buffered gzip/zstd
+/// encoder when `compress` is set and appending the matching extension.
+/// Returns the writer and the path it actually wrote to.
+fn open_output(path: &str, compress: Option<&str>) -> AnyResult<(OutputWriter, String)> {
+    match compress {
+        None => {
+            let file = File::create(path)?;
+            Ok((OutputWriter::Plain(BufWriter::new(file)), path.to_string()))
+        }
+        Some("gzip") => {
+            let out_path = format!("{path}.gz");
+            let file = File::create(&out_path)?;
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            Ok((OutputWriter::Gzip(BufWriter::new(encoder)), out_path))
+        }
+        Some("zstd") => {
+            let out_path = format!("{path}.zst");
+            let file = File::create(&out_path)?;
+            let encoder = zstd::Encoder::new(file, 0)?;
+            Ok((OutputWriter::Zstd(BufWriter::new(encoder)), out_path))
+        }
+        Some(other) => anyhow::bail!("unknown --compress value {other:?} (expected gzip or zstd)"),
+    }
+}
+
+/// Folds `other` into `dst`, merging per-key min/max/count/sum the same way
+/// both the per-worker accumulation and the final cross-worker merge do.
+fn merge_maps(dst: &mut AHashMap<Key, Record>, other: &AHashMap<Key, Record>) {
+    for (&key, rec) in other {
+        if let Some(dst_rec) = dst.get_mut(&key) {
+            dst_rec.count += rec.count;
+            dst_rec.sum += rec.sum;
+            dst_rec.max = dst_rec.max.max(rec.max);
+            dst_rec.min = dst_rec.min.min(rec.min);
+        } else {
+            dst.insert(key, *rec);
+        }
+    }
+}
+
+/// A worker claims chunk offsets off `queue` until it is empty, accumulating
+/// every chunk it processes into a single local map, and returns that map
+/// alongside the validation failures it saw (zero if `--check` is off). If
+/// `checkpoint` is set, finished offsets and their records are flushed every
+/// `CHECKPOINT_FLUSH_INTERVAL` chunks so a crash loses at most one interval
+/// of work.
+fn worker(
+    file: &File,
+    queue: &ArrayQueue<u64>,
+    check: bool,
+    quarantine: Option<&Quarantine>,
+    binary: bool,
+    checkpoint: Option<&Checkpoint>,
+) -> AnyResult<(AHashMap<Key, Record>, ErrorCounts)> {
+    let mut map = AHashMap::<Key, Record>::with_capacity(1024);
+    let mut counts = ErrorCounts::default();
+
+    // Progress accumulated since the last checkpoint flush, so each flush is
+    // a delta and replaying every surviving blob never double-counts.
+    let mut pending_map = AHashMap::<Key, Record>::new();
+    let mut pending_offsets = Vec::new();
+
+    macro_rules! maybe_flush {
+        ($l_map:expr, $offset:expr) => {
+            if let Some(checkpoint) = checkpoint {
+                merge_maps(&mut pending_map, $l_map);
+                pending_offsets.push($offset);
+                if pending_offsets.len() >= CHECKPOINT_FLUSH_INTERVAL {
+                    checkpoint.append(&pending_offsets, &pending_map)?;
+                    pending_offsets.clear();
+                    pending_map.clear();
+                }
+            }
+        };
+    }
+
+    if binary {
+        let mut buffer = [0u8; BIN_CHUNK_BYTES as usize];
+        while let Some(offset) = queue.pop() {
+            let n = read_bin_chunk(file, offset, &mut buffer);
+            let l_map = process_chunk_binary(&buffer[..n]);
+            merge_maps(&mut map, &l_map);
+            maybe_flush!(&l_map, offset);
+        }
+    } else {
+        let mut buffer = [0; (CHUNK_SIZE + CHUNK_EXCESS) as usize];
+        while let Some(offset) = queue.pop() {
+            let (start, end) = read_chunk(file, offset, &mut buffer);
+            let l_map = if check {
+                process_chunk_checked(
+                    &buffer[start..end],
+                    offset + start as u64,
+                    &mut counts,
+                    quarantine,
+                )
+            } else {
+                process_chunk_v2(&buffer[start..end])
+            };
+            merge_maps(&mut map, &l_map);
+            maybe_flush!(&l_map, offset);
+        }
+    }
+
+    if let Some(checkpoint) = checkpoint {
+        if !pending_offsets.is_empty() || !pending_map.is_empty() {
+            checkpoint.append(&pending_offsets, &pending_map)?;
+        }
+    }
+
+    Ok((map, counts))
+}
+
+struct Config {
+    path: String,
+    threads: usize,
+    check: bool,
+    quarantine: Option<String>,
+    convert: Option<String>,
+    binary: bool,
+    checkpoint: Option<String>,
+    compress: Option<String>,
+    output: String,
+}
+
+fn parse_args() -> Config {
     let mut args = args();
     _ = args.next();
-    let path = args.next().expect("file not found");
-    println!("found file: {}", path);
 
-    let file = File::open(path)?;
+    let mut path = None;
+    let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let mut check = false;
+    let mut quarantine = None;
+    let mut convert = None;
+    let mut binary = false;
+    let mut checkpoint = None;
+    let mut compress = None;
+    let mut output = "./out.txt".to_string();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => {
+                threads = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .expect("--threads requires a numeric value");
+                assert!(threads > 0, "--threads must be greater than zero");
+            }
+            "--check" => check = true,
+            "--quarantine" => {
+                quarantine = Some(args.next().expect("--quarantine requires a path"));
+                check = true;
+            }
+            "--convert" => {
+                convert = Some(args.next().expect("--convert requires an output path"));
+            }
+            "--binary" => binary = true,
+            "--checkpoint" => {
+                checkpoint = Some(args.next().expect("--checkpoint requires a path"));
+            }
+            "--compress" => {
+                compress = Some(args.next().expect("--compress requires gzip or zstd"));
+            }
+            "--output" => {
+                output = args.next().expect("--output requires a path");
+            }
+            other => path = Some(other.to_string()),
+        }
+    }
+
+    Config {
+        path: path.expect("file not found"),
+        threads,
+        check,
+        quarantine,
+        convert,
+        binary,
+        checkpoint,
+        compress,
+        output,
+    }
+}
+
+fn main() -> AnyResult<()> {
+    let config = parse_args();
+
+    if let Some(output) = &config.convert {
+        convert_to_binary(&config.path, output)?;
+        println!("converted {} -> {}", config.path, output);
+        return Ok(());
+    }
+
+    println!("found file: {}", config.path);
+
+    let file = File::open(config.path)?;
+    let quarantine = config
+        .quarantine
+        .as_deref()
+        .map(Quarantine::open)
+        .transpose()?;
+    let quarantine = quarantine.map(Arc::new);
+
+    let data_offset = if config.binary {
+        read_bin_header(&file)?
+    } else {
+        0
+    };
+    let stride = if config.binary {
+        BIN_CHUNK_BYTES
+    } else {
+        CHUNK_SIZE
+    };
+
+    let checkpoint = config.checkpoint.as_deref().map(Checkpoint::create).transpose()?;
+    let checkpoint = checkpoint.map(Arc::new);
+    let (mut map, done_offsets) = match &config.checkpoint {
+        Some(path) => recover_checkpoint(path)?,
+        None => (AHashMap::new(), HashSet::new()),
+    };
+    if !done_offsets.is_empty() {
+        println!("resumed {} chunk(s) from checkpoint", done_offsets.len());
+    }
 
     let start = Instant::now();
 
-    let mut offset = 0;
-    let (tx, rx) = mpsc::channel();
-    let mut parts = 0;
-    let creation_start = Instant::now();
     let file_len = file.metadata()?.len();
+    let chunk_count = ((file_len - data_offset) / stride) + 1;
 
+    let queue = Arc::new(ArrayQueue::<u64>::new(chunk_count as usize));
+    let mut offset = data_offset;
     while offset < file_len {
+        if !done_offsets.contains(&offset) {
+            queue.push(offset).unwrap();
+        }
+        offset += stride;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let creation_start = Instant::now();
+
+    for _ in 0..config.threads {
         let file_c = file.try_clone()?;
         let tx = tx.clone();
+        let queue = Arc::clone(&queue);
+        let quarantine = quarantine.clone();
+        let checkpoint = checkpoint.clone();
+        let check = config.check;
+        let binary = config.binary;
 
         thread::spawn(move || {
-            tx.send(dispatch(&file_c, offset, file_len)).unwrap();
+            tx.send(worker(
+                &file_c,
+                &queue,
+                check,
+                quarantine.as_deref(),
+                binary,
+                checkpoint.as_deref(),
+            ))
+            .unwrap();
         });
-
-        offset += CHUNK_SIZE * DISPATCH_LOOPS as u64;
-        parts += 1;
     }
+    drop(tx);
     let creation_finish = creation_start.elapsed();
 
-    println!("handles:{}", parts);
+    println!("threads:{}", config.threads);
     let awaiting_start = Instant::now();
-    let mut map = AHashMap::<Key, Record>::with_capacity(512);
-    for _ in 0..parts {
-        for (key, other) in rx.recv().unwrap().drain() {
-            map.entry(key)
-                .and_modify(|record| {
-                    record.count += other.count;
-                    record.sum += other.sum;
-                    record.max = record.max.max(other.max);
-                    record.min = record.min.min(other.min);
-                })
-                .or_insert(other);
-        }
+    let mut error_counts = ErrorCounts::default();
+    for result in rx {
+        let (partial, counts) = result?;
+        merge_maps(&mut map, &partial);
+        error_counts.merge(counts);
     }
 
     println!("Creation time: {:?}", creation_finish);
     println!("Awaiting time: {:?}", awaiting_start.elapsed());
     println!("Full time: {:?}", start.elapsed());
 
-    let mut file = File::create("./out.txt")?;
+    if config.check {
+        println!(
+            "Validation: {} bad record(s) (delimiter:{}, name:{}, value:{})",
+            error_counts.total(),
+            error_counts.delimiter,
+            error_counts.name,
+            error_counts.value
+        );
+    }
+
+    let (mut writer, out_path) = open_output(&config.output, config.compress.as_deref())?;
     for record in map {
         let (name, rec) = (record.0, record.1);
         let name_buff: Key = unsafe { std::mem::transmute(name) };
@@ -203,9 +957,215 @@ fn main() -> AnyResult<()> {
             rec.min as f32 / 10.0,
             rec.max as f32 / 10.0
         );
-        file.write(&line.as_bytes())?;
+        writer.write_all(line.as_bytes())?;
     }
-    file.flush()?;
+    writer.finish()?;
+    println!("wrote {}", out_path);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic_record(max: i32, min: i32, count: i32, sum: i32) -> Record {
+        Record { max, min, count, sum }
+    }
+
+    fn key_for(name: &[u8]) -> Key {
+        let mut key = Key::default();
+        key[..name.len()].copy_from_slice(name);
+        key
+    }
+
+    #[test]
+    fn binary_round_trip_through_process_chunk_binary() {
+        let mut buf = Vec::new();
+        write_bin_record(&mut buf, b"Hamburg", 125).unwrap();
+        write_bin_record(&mut buf, b"Hamburg", 98).unwrap();
+        write_bin_record(&mut buf, b"Oslo", -40).unwrap();
+
+        let map = process_chunk_binary(&buf);
+
+        let hamburg = map.get(&key_for(b"Hamburg")).expect("hamburg present");
+        assert_eq!(hamburg.count, 2);
+        assert_eq!(hamburg.sum, 125 + 98);
+        assert_eq!(hamburg.max, 125);
+        assert_eq!(hamburg.min, 98);
+
+        let oslo = map.get(&key_for(b"Oslo")).expect("oslo present");
+        assert_eq!(oslo.count, 1);
+        assert_eq!(oslo.sum, -40);
+    }
+
+    #[test]
+    fn read_bin_header_accepts_valid_and_rejects_bad_signature() {
+        let path = std::env::temp_dir().join(format!("brc_test_header_{}.bin", std::process::id()));
+
+        File::create(&path)
+            .and_then(|mut f| {
+                f.write_all(&BIN_MAGIC)?;
+                f.write_all(&[BIN_VERSION])
+            })
+            .unwrap();
+        let f = File::open(&path).unwrap();
+        assert_eq!(read_bin_header(&f).unwrap(), BIN_HEADER_LEN);
+
+        File::create(&path)
+            .and_then(|mut f| f.write_all(b"not a brc file at all"))
+            .unwrap();
+        let f = File::open(&path).unwrap();
+        assert!(read_bin_header(&f).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn progress_serialize_round_trips_through_deserialize() {
+        let mut map = AHashMap::new();
+        let key = key_for(b"Foo");
+        map.insert(key, synthetic_record(100, 10, 4, 220));
+
+        let payload = serialize_progress(&[4096, 8192], &map);
+
+        let mut offsets = Vec::new();
+        let mut restored = AHashMap::new();
+        deserialize_progress(&payload, &mut offsets, &mut restored);
+
+        assert_eq!(offsets, vec![4096, 8192]);
+        let rec = restored.get(&key).unwrap();
+        assert_eq!(rec.max, 100);
+        assert_eq!(rec.min, 10);
+        assert_eq!(rec.count, 4);
+        assert_eq!(rec.sum, 220);
+    }
+
+    #[test]
+    fn recover_checkpoint_replays_full_blobs_and_splits_large_payloads() {
+        let path = std::env::temp_dir().join(format!("brc_test_ckpt_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let checkpoint = Checkpoint::create(path.to_str().unwrap()).unwrap();
+
+        // Small enough to fit in a single Full blob.
+        let mut small_map = AHashMap::new();
+        small_map.insert(key_for(b"A"), synthetic_record(10, 10, 1, 10));
+        checkpoint.append(&[0], &small_map).unwrap();
+
+        // Big enough to force a First/Middle/Last split (> CHECKPOINT_BLOCK).
+        let mut big_map = AHashMap::new();
+        for i in 0..3000u32 {
+            let name = format!("station{i}");
+            big_map.insert(key_for(name.as_bytes()), synthetic_record(1, 1, 1, 1));
+        }
+        let big_offsets: Vec<u64> = (1..=CHECKPOINT_FLUSH_INTERVAL as u64).collect();
+        checkpoint.append(&big_offsets, &big_map).unwrap();
+        drop(checkpoint);
+
+        let (map, done) = recover_checkpoint(path.to_str().unwrap()).unwrap();
+
+        assert!(done.contains(&0));
+        for o in &big_offsets {
+            assert!(done.contains(o));
+        }
+        assert_eq!(map.len(), 1 + big_map.len());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn recover_checkpoint_stops_at_truncated_tail() {
+        let path = std::env::temp_dir().join(format!("brc_test_trunc_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let checkpoint = Checkpoint::create(path.to_str().unwrap()).unwrap();
+        let mut map = AHashMap::new();
+        map.insert(key_for(b"A"), synthetic_record(1, 1, 1, 1));
+        checkpoint.append(&[0], &map).unwrap();
+        drop(checkpoint);
+
+        // Simulate a crash mid-write: a header-sized blob whose payload never
+        // got flushed.
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 0]))
+            .unwrap();
+
+        let (recovered, done) = recover_checkpoint(path.to_str().unwrap()).unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert!(done.contains(&0));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn checked_processing_resyncs_after_malformed_row() {
+        let mut counts = ErrorCounts::default();
+        let map = process_chunk_checked(b"Oslo;10\nBerlin\nParis;20\n", 0, &mut counts, None);
+
+        assert_eq!(counts.delimiter, 1);
+        assert_eq!(counts.total(), 1);
+
+        let oslo = map.get(&key_for(b"Oslo")).expect("oslo present");
+        assert_eq!(oslo.count, 1);
+        assert_eq!(oslo.sum, 10);
+
+        let paris = map.get(&key_for(b"Paris")).expect("paris present");
+        assert_eq!(paris.count, 1);
+        assert_eq!(paris.sum, 20);
+
+        assert!(map.get(&key_for(b"Berlin")).is_none());
+    }
+
+    #[test]
+    fn checked_processing_flags_multiple_semicolons_in_one_row() {
+        let mut counts = ErrorCounts::default();
+        let map = process_chunk_checked(b"Foo;1;2\nBar;3\n", 0, &mut counts, None);
+
+        assert_eq!(counts.delimiter, 1);
+        assert!(map.get(&key_for(b"Foo")).is_none());
+
+        let bar = map.get(&key_for(b"Bar")).expect("bar present");
+        assert_eq!(bar.count, 1);
+        assert_eq!(bar.sum, 3);
+    }
+
+    #[test]
+    fn gzip_output_round_trips() {
+        use std::io::Read;
+
+        let path = std::env::temp_dir().join(format!("brc_test_gzip_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(format!("{}.gz", path.to_str().unwrap()));
+
+        let (mut writer, out_path) = open_output(path.to_str().unwrap(), Some("gzip")).unwrap();
+        assert!(out_path.ends_with(".gz"));
+        writer.write_all(b"Hamburg;125\nOslo;-40\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&out_path).unwrap())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"Hamburg;125\nOslo;-40\n");
+
+        let _ = std::fs::remove_file(&out_path);
+    }
+
+    #[test]
+    fn zstd_output_round_trips() {
+        let path = std::env::temp_dir().join(format!("brc_test_zstd_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(format!("{}.zst", path.to_str().unwrap()));
+
+        let (mut writer, out_path) = open_output(path.to_str().unwrap(), Some("zstd")).unwrap();
+        assert!(out_path.ends_with(".zst"));
+        writer.write_all(b"Hamburg;125\nOslo;-40\n").unwrap();
+        writer.finish().unwrap();
+
+        let decoded = zstd::stream::decode_all(File::open(&out_path).unwrap()).unwrap();
+        assert_eq!(decoded, b"Hamburg;125\nOslo;-40\n");
+
+        let _ = std::fs::remove_file(&out_path);
+    }
+}